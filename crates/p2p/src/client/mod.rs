@@ -0,0 +1,6 @@
+//! Client-facing command surface for the libp2p swarm driven by
+//! [`crate::MainLoop`]. [`peer_aware`] talks to a specific peer/substream;
+//! [`peer_agnostic`] picks which peer to talk to.
+
+pub mod peer_agnostic;
+pub mod peer_aware;
@@ -0,0 +1,55 @@
+//! The peer-agnostic sync client: wraps [`super::peer_aware::Client`] with
+//! knowledge of *which* connected peers are eligible sync targets, so callers
+//! don't have to track peer selection or reputation themselves.
+
+use std::sync::Arc;
+
+use libp2p::PeerId;
+use tokio::sync::RwLock;
+
+use super::peer_aware;
+use crate::Peers;
+
+#[derive(Clone, Debug)]
+pub struct Client {
+    inner: peer_aware::Client,
+    block_propagation_topic: String,
+    peers: Arc<RwLock<Peers>>,
+}
+
+impl Client {
+    pub fn new(
+        inner: peer_aware::Client,
+        block_propagation_topic: String,
+        peers: Arc<RwLock<Peers>>,
+    ) -> Self {
+        Self {
+            inner,
+            block_propagation_topic,
+            peers,
+        }
+    }
+
+    pub fn block_propagation_topic(&self) -> &str {
+        &self.block_propagation_topic
+    }
+
+    /// Picks a connected peer to issue the next sync request to, skipping
+    /// any that are currently banned (see [`crate::sync::reputation`]).
+    ///
+    /// `candidates` is the set of peers known (e.g. via gossipsub mesh
+    /// membership or capability advertisement) to serve sync protocols; this
+    /// just applies the reputation filter that picking a target should never
+    /// bypass.
+    pub async fn select_sync_target(&self, candidates: &[PeerId]) -> Option<PeerId> {
+        let peers = self.peers.read().await;
+        candidates
+            .iter()
+            .copied()
+            .find(|peer| !peers.is_banned(peer))
+    }
+
+    pub fn inner(&self) -> &peer_aware::Client {
+        &self.inner
+    }
+}
@@ -0,0 +1,369 @@
+//! The peer-aware sync client: issues requests to and sends responses
+//! towards a *specific* peer or substream. [`super::peer_agnostic`] builds on
+//! top of this to pick *which* peer to talk to.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use futures::{Stream, StreamExt};
+use libp2p::multiaddr::Multiaddr;
+use libp2p::PeerId;
+use p2p_proto::{event, header, receipt, transaction};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::sync::codec::ResponseFrame;
+
+/// The responder's end of an inbound sync request: dropping it without
+/// sending anything closes the substream with no response.
+///
+/// Streaming protocols (transactions/receipts/events/bodies) are answered by
+/// sending zero or more [`ResponseFrame::Data`] items followed by exactly one
+/// [`ResponseFrame::Fin`]; [`Client::send_headers_sync_response`]
+/// sends a single non-streaming response and is the one exception.
+///
+/// Wraps the underlying [`p2p_stream::ResponseChannel`]; it's `Clone` so the
+/// streaming `send_*_sync_response_stream` methods can reuse it across every
+/// item of a range plus the closing `Fin`.
+pub struct InboundResponseChannel<Resp>(pub(crate) p2p_stream::ResponseChannel<Resp>);
+
+impl<Resp> Clone for InboundResponseChannel<Resp> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+pub(crate) enum Command {
+    StartListening {
+        addr: Multiaddr,
+        sender: oneshot::Sender<anyhow::Result<()>>,
+    },
+    Dial {
+        peer_id: PeerId,
+        addr: Multiaddr,
+        sender: oneshot::Sender<anyhow::Result<()>>,
+    },
+    SubscribeTopic {
+        topic: String,
+        sender: oneshot::Sender<anyhow::Result<()>>,
+    },
+    ProvideCapability {
+        capability: &'static str,
+        sender: oneshot::Sender<anyhow::Result<()>>,
+    },
+    SendHeadersSyncResponse {
+        channel: InboundResponseChannel<header::BlockHeadersResponse>,
+        response: header::BlockHeadersResponse,
+    },
+    SendTransactionsSyncResponseFrame {
+        channel: InboundResponseChannel<ResponseFrame<transaction::TransactionsResponse>>,
+        frame: ResponseFrame<transaction::TransactionsResponse>,
+    },
+    SendReceiptsSyncResponseFrame {
+        channel: InboundResponseChannel<ResponseFrame<receipt::ReceiptsResponse>>,
+        frame: ResponseFrame<receipt::ReceiptsResponse>,
+    },
+    SendEventsSyncResponseFrame {
+        channel: InboundResponseChannel<ResponseFrame<event::EventsResponse>>,
+        frame: ResponseFrame<event::EventsResponse>,
+    },
+    SendBodiesSyncResponseFrame {
+        channel: InboundResponseChannel<ResponseFrame<p2p_proto_v1::block::BlockBodiesResponse>>,
+        frame: ResponseFrame<p2p_proto_v1::block::BlockBodiesResponse>,
+    },
+    /// Issues an outbound transactions sync request to `peer`; each item of
+    /// the streamed response is forwarded to `sender` as it arrives, and
+    /// `sender` is dropped once the peer's closing [`ResponseFrame::Fin`] is
+    /// observed (see [`crate::MainLoop`]'s `pending_transactions` map).
+    SendTransactionsSyncRequest {
+        peer: PeerId,
+        request: transaction::TransactionsRequest,
+        sender: mpsc::Sender<transaction::TransactionsResponse>,
+    },
+    SendReceiptsSyncRequest {
+        peer: PeerId,
+        request: receipt::ReceiptsRequest,
+        sender: mpsc::Sender<receipt::ReceiptsResponse>,
+    },
+    SendEventsSyncRequest {
+        peer: PeerId,
+        request: event::EventsRequest,
+        sender: mpsc::Sender<event::EventsResponse>,
+    },
+    SendBodiesSyncRequest {
+        peer: PeerId,
+        request: p2p_proto_v1::block::BlockBodiesRequest,
+        sender: mpsc::Sender<p2p_proto_v1::block::BlockBodiesResponse>,
+    },
+    /// Disconnects from `peer_id` immediately, e.g. after it's crossed the
+    /// misbehavior ban threshold (see [`crate::sync::reputation`]).
+    Disconnect { peer_id: PeerId },
+    /// Excludes `peer_id` from redial and from
+    /// [`super::peer_agnostic::Client`] sync target selection for `duration`.
+    BanPeer {
+        peer_id: PeerId,
+        duration: Duration,
+    },
+}
+
+/// Command-channel facade over [`crate::MainLoop`]: every method sends a
+/// [`Command`] and, where the underlying action can fail, awaits its result.
+#[derive(Clone, Debug)]
+pub struct Client {
+    sender: mpsc::Sender<Command>,
+}
+
+impl Client {
+    pub(crate) fn new(sender: mpsc::Sender<Command>) -> Self {
+        Self { sender }
+    }
+
+    async fn try_call(
+        &self,
+        f: impl FnOnce(oneshot::Sender<anyhow::Result<()>>) -> Command,
+    ) -> anyhow::Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(f(tx))
+            .await
+            .context("P2P main loop is gone")?;
+        rx.await.context("P2P main loop dropped the command")?
+    }
+
+    pub async fn start_listening(&self, addr: Multiaddr) -> anyhow::Result<()> {
+        self.try_call(|sender| Command::StartListening { addr, sender })
+            .await
+    }
+
+    pub async fn dial(&self, peer_id: PeerId, addr: Multiaddr) -> anyhow::Result<()> {
+        self.try_call(|sender| Command::Dial {
+            peer_id,
+            addr,
+            sender,
+        })
+        .await
+    }
+
+    pub async fn subscribe_topic(&self, topic: &str) -> anyhow::Result<()> {
+        self.try_call(|sender| Command::SubscribeTopic {
+            topic: topic.to_owned(),
+            sender,
+        })
+        .await
+    }
+
+    pub async fn provide_capability(&self, capability: &'static str) -> anyhow::Result<()> {
+        self.try_call(|sender| Command::ProvideCapability { capability, sender })
+            .await
+    }
+
+    pub async fn send_headers_sync_response(
+        &self,
+        channel: InboundResponseChannel<header::BlockHeadersResponse>,
+        response: header::BlockHeadersResponse,
+    ) {
+        let _ = self
+            .sender
+            .send(Command::SendHeadersSyncResponse { channel, response })
+            .await;
+    }
+
+    /// Forwards each item of `responses` to the peer as it arrives, then
+    /// sends a closing [`ResponseFrame::Fin`] — see
+    /// [`crate::sync::codec::StreamingSyncCodec`].
+    pub async fn send_transactions_sync_response_stream(
+        &self,
+        channel: InboundResponseChannel<ResponseFrame<transaction::TransactionsResponse>>,
+        mut responses: impl Stream<Item = transaction::TransactionsResponse> + Unpin,
+    ) {
+        while let Some(response) = responses.next().await {
+            if self
+                .sender
+                .send(Command::SendTransactionsSyncResponseFrame {
+                    channel: InboundResponseChannel(channel.0.clone()),
+                    frame: ResponseFrame::Data(response),
+                })
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+        let _ = self
+            .sender
+            .send(Command::SendTransactionsSyncResponseFrame {
+                channel,
+                frame: ResponseFrame::Fin,
+            })
+            .await;
+    }
+
+    pub async fn send_receipts_sync_response_stream(
+        &self,
+        channel: InboundResponseChannel<ResponseFrame<receipt::ReceiptsResponse>>,
+        mut responses: impl Stream<Item = receipt::ReceiptsResponse> + Unpin,
+    ) {
+        while let Some(response) = responses.next().await {
+            if self
+                .sender
+                .send(Command::SendReceiptsSyncResponseFrame {
+                    channel: InboundResponseChannel(channel.0.clone()),
+                    frame: ResponseFrame::Data(response),
+                })
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+        let _ = self
+            .sender
+            .send(Command::SendReceiptsSyncResponseFrame {
+                channel,
+                frame: ResponseFrame::Fin,
+            })
+            .await;
+    }
+
+    pub async fn send_events_sync_response_stream(
+        &self,
+        channel: InboundResponseChannel<ResponseFrame<event::EventsResponse>>,
+        mut responses: impl Stream<Item = event::EventsResponse> + Unpin,
+    ) {
+        while let Some(response) = responses.next().await {
+            if self
+                .sender
+                .send(Command::SendEventsSyncResponseFrame {
+                    channel: InboundResponseChannel(channel.0.clone()),
+                    frame: ResponseFrame::Data(response),
+                })
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+        let _ = self
+            .sender
+            .send(Command::SendEventsSyncResponseFrame {
+                channel,
+                frame: ResponseFrame::Fin,
+            })
+            .await;
+    }
+
+    /// Bodies runs over the legacy (pre-`p2p::sync`) protocol but is
+    /// streamed the same way as the newer range protocols.
+    pub async fn send_bodies_sync_response_stream(
+        &self,
+        channel: InboundResponseChannel<ResponseFrame<p2p_proto_v1::block::BlockBodiesResponse>>,
+        mut responses: impl Stream<Item = p2p_proto_v1::block::BlockBodiesResponse> + Unpin,
+    ) {
+        while let Some(response) = responses.next().await {
+            if self
+                .sender
+                .send(Command::SendBodiesSyncResponseFrame {
+                    channel: InboundResponseChannel(channel.0.clone()),
+                    frame: ResponseFrame::Data(response),
+                })
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+        let _ = self
+            .sender
+            .send(Command::SendBodiesSyncResponseFrame {
+                channel,
+                frame: ResponseFrame::Fin,
+            })
+            .await;
+    }
+
+    /// Issues a transactions sync request to `peer` and returns a channel
+    /// that yields each [`transaction::TransactionsResponse`] item as it
+    /// streams in off the wire, closing once the peer's [`ResponseFrame::Fin`]
+    /// is observed — see [`crate::sync::codec::StreamingSyncCodec`]. Items
+    /// are decoded one frame at a time, and the returned channel buffers at
+    /// most 1024 of them, so peak memory does not grow with the whole range.
+    pub async fn send_transactions_sync_request(
+        &self,
+        peer: PeerId,
+        request: transaction::TransactionsRequest,
+    ) -> anyhow::Result<mpsc::Receiver<transaction::TransactionsResponse>> {
+        let (sender, receiver) = mpsc::channel(1024);
+        self.sender
+            .send(Command::SendTransactionsSyncRequest {
+                peer,
+                request,
+                sender,
+            })
+            .await
+            .context("P2P main loop is gone")?;
+        Ok(receiver)
+    }
+
+    pub async fn send_receipts_sync_request(
+        &self,
+        peer: PeerId,
+        request: receipt::ReceiptsRequest,
+    ) -> anyhow::Result<mpsc::Receiver<receipt::ReceiptsResponse>> {
+        let (sender, receiver) = mpsc::channel(1024);
+        self.sender
+            .send(Command::SendReceiptsSyncRequest {
+                peer,
+                request,
+                sender,
+            })
+            .await
+            .context("P2P main loop is gone")?;
+        Ok(receiver)
+    }
+
+    pub async fn send_events_sync_request(
+        &self,
+        peer: PeerId,
+        request: event::EventsRequest,
+    ) -> anyhow::Result<mpsc::Receiver<event::EventsResponse>> {
+        let (sender, receiver) = mpsc::channel(1024);
+        self.sender
+            .send(Command::SendEventsSyncRequest {
+                peer,
+                request,
+                sender,
+            })
+            .await
+            .context("P2P main loop is gone")?;
+        Ok(receiver)
+    }
+
+    /// Bodies runs over the legacy (pre-`p2p::sync`) protocol but is
+    /// streamed the same way as the newer range protocols.
+    pub async fn send_bodies_sync_request(
+        &self,
+        peer: PeerId,
+        request: p2p_proto_v1::block::BlockBodiesRequest,
+    ) -> anyhow::Result<mpsc::Receiver<p2p_proto_v1::block::BlockBodiesResponse>> {
+        let (sender, receiver) = mpsc::channel(1024);
+        self.sender
+            .send(Command::SendBodiesSyncRequest {
+                peer,
+                request,
+                sender,
+            })
+            .await
+            .context("P2P main loop is gone")?;
+        Ok(receiver)
+    }
+
+    pub async fn disconnect(&self, peer_id: PeerId) {
+        let _ = self.sender.send(Command::Disconnect { peer_id }).await;
+    }
+
+    pub async fn ban_peer(&self, peer_id: PeerId, duration: Duration) {
+        let _ = self
+            .sender
+            .send(Command::BanPeer { peer_id, duration })
+            .await;
+    }
+}
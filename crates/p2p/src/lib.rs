@@ -0,0 +1,671 @@
+//! libp2p-based peer-to-peer networking for Starknet sync and block
+//! propagation.
+//!
+//! [`sync`] defines the request/streaming-response protocols and their wire
+//! codec, [`peers`] tracks connection state and misbehavior reputation, and
+//! [`client`] is the command-channel facade [`new`] hands back to callers;
+//! [`MainLoop`] is the task that actually drives the libp2p `Swarm` and
+//! translates its events into [`Event`]s.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+pub use libp2p;
+use libp2p::identity::Keypair;
+use libp2p::swarm::SwarmEvent;
+use libp2p::PeerId;
+use p2p_proto::{event, header, receipt, transaction};
+use pathfinder_common::{BlockHash, BlockNumber};
+use tokio::sync::{mpsc, watch, RwLock};
+
+mod behaviour;
+pub mod client;
+pub mod peers;
+pub mod sync;
+
+use behaviour::{Behaviour, BehaviourEvent};
+use client::peer_aware::{Command, InboundResponseChannel};
+use sync::codec::ResponseFrame;
+use sync::metrics::SyncMetrics;
+use sync::reputation::Misbehavior;
+
+pub use peers::Peers;
+
+pub type HeadTx = watch::Sender<Option<(BlockNumber, BlockHash)>>;
+pub type HeadRx = watch::Receiver<Option<(BlockNumber, BlockHash)>>;
+
+/// Behaviour toggles for [`new`], beyond the bootstrap addresses and
+/// keypair supplied separately.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Enables mDNS so nodes on the same LAN auto-discover and dial each
+    /// other without an explicit bootstrap address. Discovered peers are
+    /// registered into the shared [`Peers`] map and become eligible sync
+    /// targets, same as any explicitly-dialed peer.
+    pub enable_mdns: bool,
+    /// Enables zstd compression of outgoing sync responses (see
+    /// [`sync::codec::SyncCodec::with_compression`]). Incoming responses are
+    /// decompressed regardless of this setting, so peers can mix it freely.
+    pub enable_compression: bool,
+}
+
+/// Events surfaced from the swarm to `pathfinder::p2p_network::handle_p2p_event`.
+#[derive(Debug)]
+pub enum Event {
+    InboundHeadersSyncRequest {
+        from: PeerId,
+        request: header::BlockHeadersRequest,
+        channel: InboundResponseChannel<header::BlockHeadersResponse>,
+    },
+    InboundBodiesSyncRequest {
+        from: PeerId,
+        request: p2p_proto_v1::block::BlockBodiesRequest,
+        channel: InboundResponseChannel<ResponseFrame<p2p_proto_v1::block::BlockBodiesResponse>>,
+    },
+    InboundTransactionsSyncRequest {
+        from: PeerId,
+        request: transaction::TransactionsRequest,
+        channel: InboundResponseChannel<ResponseFrame<transaction::TransactionsResponse>>,
+    },
+    InboundReceiptsSyncRequest {
+        from: PeerId,
+        request: receipt::ReceiptsRequest,
+        channel: InboundResponseChannel<ResponseFrame<receipt::ReceiptsResponse>>,
+    },
+    InboundEventsSyncRequest {
+        from: PeerId,
+        request: event::EventsRequest,
+        channel: InboundResponseChannel<ResponseFrame<event::EventsResponse>>,
+    },
+    BlockPropagation {
+        from: PeerId,
+        new_block: p2p_proto_v1::block::NewBlock,
+    },
+    /// A protocol violation observed while decoding a request or response
+    /// from `from` (oversized frame, malformed protobuf, ...), surfaced from
+    /// the relevant sync protocol's `InboundFailure`/`OutboundFailure` swarm
+    /// event.
+    ProtocolViolation { from: PeerId, violation: Misbehavior },
+    SyncPeerConnected { peer_id: PeerId },
+    SyncPeerDisconnected { peer_id: PeerId },
+    Test(TestEvent),
+}
+
+/// Placeholder inner type for [`Event::Test`], used by integration tests
+/// outside this crate to inject synthetic events into `handle_p2p_event`.
+#[derive(Debug)]
+pub struct TestEvent;
+
+/// Constructs the client/event-stream/main-loop triple used by
+/// `pathfinder::p2p_network::start`: `client` issues commands, `events`
+/// yields [`Event`]s, and `main_loop.run()` must be spawned to actually drive
+/// the two. `metrics` is forwarded to every sync codec (see
+/// [`behaviour::build_swarm`]) so response sizes are actually recorded
+/// instead of `sync_response_bytes` always reading empty.
+pub fn new(
+    keypair: Keypair,
+    peers: Arc<RwLock<Peers>>,
+    config: Config,
+    metrics: Option<Arc<SyncMetrics>>,
+) -> (
+    client::peer_aware::Client,
+    mpsc::Receiver<Event>,
+    MainLoop,
+) {
+    let (command_tx, command_rx) = mpsc::channel(1024);
+    let (event_tx, event_rx) = mpsc::channel(1024);
+
+    let client = client::peer_aware::Client::new(command_tx);
+    let main_loop = MainLoop::new(keypair, peers, config, metrics, command_rx, event_tx);
+
+    (client, event_rx, main_loop)
+}
+
+/// Classifies a `p2p_stream::Event::{Inbound,Outbound}Failure` error as a
+/// [`Misbehavior`], if it's the kind of failure a well-behaved peer would
+/// never trigger. This is how [`Event::ProtocolViolation`] actually gets
+/// emitted: every message this matches originates as an `io::Error`
+/// constructed in [`sync::codec`]'s `read_request`/`read_response` (oversized
+/// frame, unknown codec/frame tag, malformed protobuf), which `p2p_stream`
+/// surfaces back to us wrapped in one of these two failure kinds.
+///
+/// Connection-level failures (timeouts, dial failures, unsupported
+/// protocols) don't match anything here and are left unclassified: those are
+/// just as likely to be network conditions as a hostile peer, so they
+/// shouldn't cost a peer reputation.
+fn classify_failure(error: &dyn std::fmt::Display) -> Option<Misbehavior> {
+    let message = error.to_string();
+    if message.contains("exceeds the maximum") || message.contains("Unknown codec tag")
+        || message.contains("Unknown frame tag")
+    {
+        Some(Misbehavior::OversizedFrame)
+    } else if message.contains("decode") || message.contains("Decode") {
+        Some(Misbehavior::MalformedPayload)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_failure_flags_oversized_frames() {
+        assert_eq!(
+            classify_failure(&"Request exceeds the maximum size of 131072 bytes"),
+            Some(Misbehavior::OversizedFrame)
+        );
+        assert_eq!(
+            classify_failure(&"Unknown codec tag 7"),
+            Some(Misbehavior::OversizedFrame)
+        );
+        assert_eq!(
+            classify_failure(&"Unknown frame tag 3"),
+            Some(Misbehavior::OversizedFrame)
+        );
+    }
+
+    #[test]
+    fn classify_failure_flags_malformed_payloads() {
+        assert_eq!(
+            classify_failure(&"failed to decode Protobuf message"),
+            Some(Misbehavior::MalformedPayload)
+        );
+    }
+
+    #[test]
+    fn classify_failure_ignores_connection_level_errors() {
+        assert_eq!(classify_failure(&"timed out"), None);
+        assert_eq!(classify_failure(&"connection reset by peer"), None);
+    }
+
+    /// `p2p_stream::{Inbound,Outbound}Failure` doesn't wrap its `io::Error`
+    /// transparently -- its `Display` impl prefixes it with its own context
+    /// (we can't construct the real upstream type here without vendoring
+    /// `p2p_stream`, so this stands in for "some outer layer wraps our
+    /// message"). Guards against `classify_failure`'s substring matching only
+    /// working against the raw, unwrapped `io::Error` message.
+    struct WrappedIoError(std::io::Error);
+
+    impl std::fmt::Display for WrappedIoError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Io(Custom {{ kind: InvalidData, error: \"{}\" }})", self.0)
+        }
+    }
+
+    #[test]
+    fn classify_failure_survives_an_outer_wrapping_layer() {
+        let oversized = WrappedIoError(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Request exceeds the maximum size of 131072 bytes",
+        ));
+        assert_eq!(
+            classify_failure(&oversized),
+            Some(Misbehavior::OversizedFrame)
+        );
+
+        let malformed = WrappedIoError(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "failed to decode Protobuf message",
+        ));
+        assert_eq!(
+            classify_failure(&malformed),
+            Some(Misbehavior::MalformedPayload)
+        );
+    }
+}
+
+/// Drives the libp2p [`Swarm`](libp2p::swarm::Swarm): executes [`Command`]s
+/// issued by [`client::peer_aware::Client`] against it and translates its
+/// events into [`Event`]s, including classifying sync codec failures into
+/// [`Event::ProtocolViolation`] and, when [`Config::enable_mdns`] is set,
+/// registering newly-discovered peers into the shared [`Peers`] map.
+pub struct MainLoop {
+    swarm: libp2p::swarm::Swarm<Behaviour>,
+    peers: Arc<RwLock<Peers>>,
+    command_rx: mpsc::Receiver<Command>,
+    event_tx: mpsc::Sender<Event>,
+    /// Outstanding outbound streaming sync requests, keyed by the
+    /// `p2p_stream::OutboundRequestId` the behaviour handed back when the
+    /// request was sent. `handle_behaviour_event` forwards each
+    /// [`ResponseFrame::Data`] item to the matching sender as it arrives and
+    /// removes the entry once it sees [`ResponseFrame::Fin`] (or the request
+    /// fails outright), which is what ends the caller's `mpsc::Receiver`.
+    pending_transactions:
+        HashMap<p2p_stream::OutboundRequestId, mpsc::Sender<transaction::TransactionsResponse>>,
+    pending_receipts: HashMap<p2p_stream::OutboundRequestId, mpsc::Sender<receipt::ReceiptsResponse>>,
+    pending_events: HashMap<p2p_stream::OutboundRequestId, mpsc::Sender<event::EventsResponse>>,
+    pending_bodies: HashMap<
+        p2p_stream::OutboundRequestId,
+        mpsc::Sender<p2p_proto_v1::block::BlockBodiesResponse>,
+    >,
+}
+
+impl MainLoop {
+    fn new(
+        keypair: Keypair,
+        peers: Arc<RwLock<Peers>>,
+        config: Config,
+        metrics: Option<Arc<SyncMetrics>>,
+        command_rx: mpsc::Receiver<Command>,
+        event_tx: mpsc::Sender<Event>,
+    ) -> Self {
+        let swarm = behaviour::build_swarm(
+            keypair,
+            config.enable_mdns,
+            config.enable_compression,
+            metrics,
+        )
+        .expect("building the libp2p swarm");
+
+        Self {
+            swarm,
+            peers,
+            command_rx,
+            event_tx,
+            pending_transactions: HashMap::new(),
+            pending_receipts: HashMap::new(),
+            pending_events: HashMap::new(),
+            pending_bodies: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            tokio::select! {
+                Some(command) = self.command_rx.recv() => self.handle_command(command).await,
+                Some(event) = self.swarm.next() => self.handle_swarm_event(event).await,
+                else => break,
+            }
+        }
+    }
+
+    async fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::StartListening { addr, sender } => {
+                let result = self
+                    .swarm
+                    .listen_on(addr)
+                    .map(|_| ())
+                    .map_err(|error| anyhow::anyhow!(error.to_string()));
+                let _ = sender.send(result);
+            }
+            Command::Dial {
+                peer_id,
+                addr,
+                sender,
+            } => {
+                let opts = libp2p::swarm::dial_opts::DialOpts::peer_id(peer_id)
+                    .addresses(vec![addr])
+                    .build();
+                let result = self
+                    .swarm
+                    .dial(opts)
+                    .map_err(|error| anyhow::anyhow!(error.to_string()));
+                let _ = sender.send(result);
+            }
+            Command::SubscribeTopic { topic, sender } => {
+                let result = self
+                    .swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .subscribe(&libp2p::gossipsub::IdentTopic::new(topic))
+                    .map(|_| ())
+                    .map_err(|error| anyhow::anyhow!(error.to_string()));
+                let _ = sender.send(result);
+            }
+            Command::ProvideCapability { capability, sender } => {
+                // Capability advertisement piggybacks on gossipsub topic
+                // subscription: peers interested in a capability subscribe
+                // to a topic of the same name.
+                let result = self
+                    .swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .subscribe(&libp2p::gossipsub::IdentTopic::new(capability))
+                    .map(|_| ())
+                    .map_err(|error| anyhow::anyhow!(error.to_string()));
+                let _ = sender.send(result);
+            }
+            Command::SendHeadersSyncResponse { channel, response } => {
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .headers
+                    .send_response(channel.0, response);
+            }
+            Command::SendTransactionsSyncResponseFrame { channel, frame } => {
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .transactions
+                    .send_response(channel.0, frame);
+            }
+            Command::SendReceiptsSyncResponseFrame { channel, frame } => {
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .receipts
+                    .send_response(channel.0, frame);
+            }
+            Command::SendEventsSyncResponseFrame { channel, frame } => {
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .events
+                    .send_response(channel.0, frame);
+            }
+            Command::SendBodiesSyncResponseFrame { channel, frame } => {
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .bodies
+                    .send_response(channel.0, frame);
+            }
+            Command::SendTransactionsSyncRequest {
+                peer,
+                request,
+                sender,
+            } => {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .transactions
+                    .send_request(&peer, request);
+                self.pending_transactions.insert(request_id, sender);
+            }
+            Command::SendReceiptsSyncRequest {
+                peer,
+                request,
+                sender,
+            } => {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .receipts
+                    .send_request(&peer, request);
+                self.pending_receipts.insert(request_id, sender);
+            }
+            Command::SendEventsSyncRequest {
+                peer,
+                request,
+                sender,
+            } => {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .events
+                    .send_request(&peer, request);
+                self.pending_events.insert(request_id, sender);
+            }
+            Command::SendBodiesSyncRequest {
+                peer,
+                request,
+                sender,
+            } => {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .bodies
+                    .send_request(&peer, request);
+                self.pending_bodies.insert(request_id, sender);
+            }
+            Command::Disconnect { peer_id } => {
+                let _ = self.swarm.disconnect_peer_id(peer_id);
+                self.peers.write().await.set_connected(peer_id, false);
+            }
+            Command::BanPeer { peer_id, duration } => {
+                self.peers.write().await.ban(peer_id, duration);
+                let _ = self.swarm.disconnect_peer_id(peer_id);
+            }
+        }
+    }
+
+    async fn handle_swarm_event(&mut self, event: SwarmEvent<BehaviourEvent>) {
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                self.peers.write().await.set_connected(peer_id, true);
+                let _ = self.event_tx.send(Event::SyncPeerConnected { peer_id }).await;
+            }
+            SwarmEvent::ConnectionClosed { peer_id, .. } => {
+                self.peers.write().await.set_connected(peer_id, false);
+                let _ = self
+                    .event_tx
+                    .send(Event::SyncPeerDisconnected { peer_id })
+                    .await;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns_event)) => {
+                self.handle_mdns_event(mdns_event).await
+            }
+            SwarmEvent::Behaviour(other) => self.handle_behaviour_event(other).await,
+            _ => {}
+        }
+    }
+
+    async fn handle_mdns_event(&mut self, event: libp2p::mdns::Event) {
+        match event {
+            libp2p::mdns::Event::Discovered(discovered) => {
+                for (peer_id, addr) in discovered {
+                    // A peer we've already banned (e.g. for a protocol
+                    // violation) shouldn't get redialed just because mDNS
+                    // re-announces it on the LAN.
+                    if self.peers.read().await.is_banned(&peer_id) {
+                        tracing::debug!(%peer_id, %addr, "Ignoring mDNS discovery of banned peer");
+                        continue;
+                    }
+                    tracing::debug!(%peer_id, %addr, "Discovered peer via mDNS");
+                    // Don't mark it connected here: if the dial fails or
+                    // never completes, nothing would ever flip it back (no
+                    // ConnectionClosed fires for a connection that never
+                    // existed). handle_swarm_event's ConnectionEstablished
+                    // arm covers this the same way it does for
+                    // explicitly-dialed peers.
+                    let opts = libp2p::swarm::dial_opts::DialOpts::peer_id(peer_id)
+                        .addresses(vec![addr])
+                        .build();
+                    let _ = self.swarm.dial(opts);
+                }
+            }
+            libp2p::mdns::Event::Expired(expired) => {
+                for (peer_id, _) in expired {
+                    self.peers.write().await.set_connected(peer_id, false);
+                }
+            }
+        }
+    }
+
+    async fn handle_behaviour_event(&mut self, event: BehaviourEvent) {
+        use p2p_stream::{Event as StreamEvent, Message};
+
+        macro_rules! handle_inbound {
+            ($event:expr, $wrap:expr) => {
+                match $event {
+                    StreamEvent::Message { peer, message } => match message {
+                        Message::Request {
+                            request, channel, ..
+                        } => {
+                            let item = $wrap(peer, request, InboundResponseChannel(channel));
+                            let _ = self.event_tx.send(item).await;
+                        }
+                        Message::Response { .. } => {}
+                    },
+                    StreamEvent::InboundFailure { peer, error, .. }
+                    | StreamEvent::OutboundFailure { peer, error, .. } => {
+                        if let Some(violation) = classify_failure(&error) {
+                            let _ = self
+                                .event_tx
+                                .send(Event::ProtocolViolation {
+                                    from: peer,
+                                    violation,
+                                })
+                                .await;
+                        }
+                    }
+                    StreamEvent::ResponseSent { .. } => {}
+                }
+            };
+        }
+
+        // Same as `handle_inbound!`, but also routes `Message::Response`
+        // frames to the pending outbound request that's waiting on them
+        // (see `MainLoop::pending_transactions` and friends): `Data` items
+        // are forwarded to the caller's `mpsc::Receiver` one at a time, and
+        // the entry is dropped -- ending that receiver -- once `Fin` arrives
+        // or the request fails outright. Used by the range protocols, which
+        // are the only ones this crate issues outbound requests for.
+        macro_rules! handle_streaming {
+            ($event:expr, $wrap:expr, $pending:expr) => {
+                match $event {
+                    StreamEvent::Message { peer, message } => match message {
+                        Message::Request {
+                            request, channel, ..
+                        } => {
+                            let item = $wrap(peer, request, InboundResponseChannel(channel));
+                            let _ = self.event_tx.send(item).await;
+                        }
+                        Message::Response {
+                            request_id,
+                            response,
+                        } => match response {
+                            ResponseFrame::Data(item) => {
+                                let gone = match $pending.get(&request_id) {
+                                    Some(sender) => sender.send(item).await.is_err(),
+                                    None => false,
+                                };
+                                if gone {
+                                    $pending.remove(&request_id);
+                                }
+                            }
+                            ResponseFrame::Fin => {
+                                $pending.remove(&request_id);
+                            }
+                        },
+                    },
+                    StreamEvent::InboundFailure { peer, error, .. } => {
+                        if let Some(violation) = classify_failure(&error) {
+                            let _ = self
+                                .event_tx
+                                .send(Event::ProtocolViolation {
+                                    from: peer,
+                                    violation,
+                                })
+                                .await;
+                        }
+                    }
+                    StreamEvent::OutboundFailure {
+                        peer,
+                        request_id,
+                        error,
+                        ..
+                    } => {
+                        // The request will never see a Data/Fin frame now --
+                        // drop its sender so the caller's receiver ends
+                        // instead of waiting forever.
+                        $pending.remove(&request_id);
+                        if let Some(violation) = classify_failure(&error) {
+                            let _ = self
+                                .event_tx
+                                .send(Event::ProtocolViolation {
+                                    from: peer,
+                                    violation,
+                                })
+                                .await;
+                        }
+                    }
+                    StreamEvent::ResponseSent { .. } => {}
+                }
+            };
+        }
+
+        match event {
+            BehaviourEvent::Headers(event) => {
+                handle_inbound!(event, |from, request, channel| {
+                    Event::InboundHeadersSyncRequest {
+                        from,
+                        request,
+                        channel,
+                    }
+                });
+            }
+            BehaviourEvent::Bodies(event) => {
+                handle_streaming!(
+                    event,
+                    |from, request, channel| {
+                        Event::InboundBodiesSyncRequest {
+                            from,
+                            request,
+                            channel,
+                        }
+                    },
+                    self.pending_bodies
+                );
+            }
+            BehaviourEvent::Transactions(event) => {
+                handle_streaming!(
+                    event,
+                    |from, request, channel| {
+                        Event::InboundTransactionsSyncRequest {
+                            from,
+                            request,
+                            channel,
+                        }
+                    },
+                    self.pending_transactions
+                );
+            }
+            BehaviourEvent::Receipts(event) => {
+                handle_streaming!(
+                    event,
+                    |from, request, channel| {
+                        Event::InboundReceiptsSyncRequest {
+                            from,
+                            request,
+                            channel,
+                        }
+                    },
+                    self.pending_receipts
+                );
+            }
+            BehaviourEvent::Events(event) => {
+                handle_streaming!(
+                    event,
+                    |from, request, channel| {
+                        Event::InboundEventsSyncRequest {
+                            from,
+                            request,
+                            channel,
+                        }
+                    },
+                    self.pending_events
+                );
+            }
+            BehaviourEvent::StateDiffs(_) | BehaviourEvent::Classes(_) => {
+                // Not yet wired into `Event`: nothing in this crate serves
+                // inbound state diff/class requests over the new protocol.
+            }
+            BehaviourEvent::Gossipsub(libp2p::gossipsub::Event::Message {
+                propagation_source,
+                message,
+                ..
+            }) => {
+                if let Ok(new_block) =
+                    <p2p_proto_v1::block::NewBlock as prost::Message>::decode(message.data.as_slice())
+                        .map_err(|_| ())
+                {
+                    let _ = self
+                        .event_tx
+                        .send(Event::BlockPropagation {
+                            from: propagation_source,
+                            new_block,
+                        })
+                        .await;
+                }
+            }
+            BehaviourEvent::Gossipsub(_) => {}
+            BehaviourEvent::Mdns(_) => unreachable!("handled in handle_swarm_event"),
+        }
+    }
+}
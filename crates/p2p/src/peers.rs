@@ -0,0 +1,131 @@
+//! Tracks connection state and misbehavior reputation for sync peers.
+//!
+//! Shared behind an `Arc<RwLock<_>>` between [`crate::MainLoop`] (which
+//! records connects/disconnects and bans) and `pathfinder::p2p_network`
+//! (which records misbehavior and reads the gauges exposed here into
+//! [`crate::sync::metrics::SyncMetrics`]).
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+
+use crate::sync::reputation::BAN_THRESHOLD;
+
+#[derive(Debug, Default)]
+struct PeerState {
+    connected: bool,
+    reputation: i32,
+    banned_until: Option<Instant>,
+}
+
+impl PeerState {
+    fn is_banned(&self, now: Instant) -> bool {
+        self.banned_until.is_some_and(|until| until > now)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Peers {
+    peers: HashMap<PeerId, PeerState>,
+}
+
+impl Peers {
+    /// Number of peers currently marked connected.
+    pub fn connected_count(&self) -> usize {
+        self.peers.values().filter(|p| p.connected).count()
+    }
+
+    /// Number of peers currently serving out a ban.
+    pub fn banned_count(&self) -> usize {
+        let now = Instant::now();
+        self.peers.values().filter(|p| p.is_banned(now)).count()
+    }
+
+    /// Whether `peer` is currently banned and should be skipped for redial
+    /// and sync target selection.
+    pub fn is_banned(&self, peer: &PeerId) -> bool {
+        self.peers
+            .get(peer)
+            .is_some_and(|p| p.is_banned(Instant::now()))
+    }
+
+    pub fn set_connected(&mut self, peer: PeerId, connected: bool) {
+        self.peers.entry(peer).or_default().connected = connected;
+    }
+
+    /// Applies `penalty` to `peer`'s reputation score and returns whether it
+    /// has now crossed [`BAN_THRESHOLD`].
+    pub fn record_misbehavior(&mut self, peer: PeerId, penalty: i32) -> bool {
+        let state = self.peers.entry(peer).or_default();
+        state.reputation = state.reputation.saturating_add(penalty);
+        state.reputation >= BAN_THRESHOLD
+    }
+
+    /// Bans `peer` for `duration`, also marking it disconnected.
+    pub fn ban(&mut self, peer: PeerId, duration: Duration) {
+        let state = self.peers.entry(peer).or_default();
+        state.banned_until = Some(Instant::now() + duration);
+        state.connected = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use libp2p::PeerId;
+
+    use super::Peers;
+    use crate::sync::reputation::{Misbehavior, BAN_THRESHOLD};
+
+    #[test]
+    fn record_misbehavior_bans_at_threshold() {
+        let peer = PeerId::random();
+        let mut peers = Peers::default();
+
+        let penalty = Misbehavior::OversizedFrame.penalty();
+        let mut banned = false;
+        let mut accumulated = 0;
+        while accumulated < BAN_THRESHOLD {
+            banned = peers.record_misbehavior(peer, penalty);
+            accumulated += penalty;
+        }
+
+        assert!(banned);
+        assert!(!peers.is_banned(&peer), "record_misbehavior alone doesn't ban");
+
+        peers.ban(peer, Duration::from_secs(60));
+        assert!(peers.is_banned(&peer));
+        assert_eq!(peers.banned_count(), 1);
+    }
+
+    #[test]
+    fn ban_outlives_later_set_connected_calls() {
+        // Regression guard for MainLoop::handle_mdns_event: rediscovering a
+        // banned peer on the LAN must not clear its ban just because
+        // something calls set_connected(peer, true) on it.
+        let peer = PeerId::random();
+        let mut peers = Peers::default();
+
+        peers.ban(peer, Duration::from_secs(60));
+        assert!(peers.is_banned(&peer));
+
+        peers.set_connected(peer, true);
+        assert!(peers.is_banned(&peer), "set_connected must not clear a ban");
+    }
+
+    #[test]
+    fn connected_count_reflects_set_connected() {
+        let mut peers = Peers::default();
+        let a = PeerId::random();
+        let b = PeerId::random();
+
+        peers.set_connected(a, true);
+        peers.set_connected(b, true);
+        assert_eq!(peers.connected_count(), 2);
+
+        peers.set_connected(a, false);
+        assert_eq!(peers.connected_count(), 1);
+    }
+}
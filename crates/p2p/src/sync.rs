@@ -1,4 +1,26 @@
 //! request/streaming-response protocol and codec definitions for sync
+//!
+//! Responses for range-based protocols (transactions, receipts, events,
+//! bodies) are not sent as a single aggregated message: the responder writes
+//! one length-delimited [`codec::ResponseFrame::Data`] item per call to
+//! [`codec::StreamingSyncCodec::write_response`] as soon as its handler
+//! produces it, followed by a final [`codec::ResponseFrame::Fin`] frame once
+//! the handler's channel is exhausted. On the requester side, `p2p_stream`
+//! calls [`codec::StreamingSyncCodec::read_response`] once per frame and
+//! surfaces each as its own swarm event; [`crate::MainLoop`] forwards every
+//! `Data` item to the `mpsc::Receiver` that
+//! [`crate::client::peer_aware::Client::send_transactions_sync_request`] (and
+//! its receipts/events/bodies equivalents) handed back to the caller, and
+//! drops that channel the moment it observes `Fin`. The codec decodes one
+//! item per frame and that channel holds at most 1024 items, so peak memory
+//! is bounded by that buffer rather than by the whole requested range.
+//!
+//! Each response payload (and, for streaming protocols, each `Data` frame's
+//! payload; `Fin` carries none) is additionally prefixed with a one-byte codec tag
+//! (see [`codec::SyncCodec::with_compression`]) identifying whether the
+//! `unsigned_varint`-length-delimited payload that follows is raw protobuf or
+//! zstd-compressed protobuf, so peers running either codec version can
+//! interoperate.
 
 pub mod protocol {
     macro_rules! define_protocol {
@@ -24,6 +46,10 @@ pub mod protocol {
     define_protocol!(Transactions, "/starknet/transactions/1");
     define_protocol!(Receipts, "/starknet/receipts/1");
     define_protocol!(Events, "/starknet/events/1");
+    // Legacy (pre-`p2p::sync`) protocol, kept out of `PROTOCOLS` below since
+    // it predates this module's protocol list and isn't part of the set this
+    // module's metrics/reputation were designed against.
+    define_protocol!(Bodies, "/starknet/bodies/1");
 
     pub const PROTOCOLS: &[&str] = &[
         Headers::NAME,
@@ -35,8 +61,122 @@ pub mod protocol {
     ];
 }
 
+/// OpenMetrics instrumentation for the sync protocols, registered once by
+/// `pathfinder::p2p_network::start` and threaded into both [`codec::SyncCodec`] (for the
+/// per-message byte histogram) and the sync event loop (for request/response
+/// counters, handler latency and peer gauges).
+pub mod metrics {
+    use std::sync::Arc;
+
+    use prometheus_client::encoding::EncodeLabelSet;
+    use prometheus_client::metrics::counter::Counter;
+    use prometheus_client::metrics::family::Family;
+    use prometheus_client::metrics::gauge::Gauge;
+    use prometheus_client::metrics::histogram::Histogram;
+    use prometheus_client::registry::Registry;
+
+    #[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+    pub struct ProtocolLabel {
+        pub protocol: String,
+    }
+
+    /// Counters/histograms/gauges for the six sync protocols
+    /// (`Headers`/`StateDiffs`/`Classes`/`Transactions`/`Receipts`/`Events`)
+    /// plus block propagation, mirroring how libp2p's own `metrics` crate
+    /// hangs per-protocol recorders off the behaviour.
+    #[derive(Debug)]
+    pub struct SyncMetrics {
+        pub inbound_requests: Family<ProtocolLabel, Counter>,
+        pub inbound_responses: Family<ProtocolLabel, Counter>,
+        pub response_bytes: Family<ProtocolLabel, Histogram>,
+        pub handler_latency_seconds: Family<ProtocolLabel, Histogram>,
+        pub connected_peers: Gauge,
+        pub banned_peers: Gauge,
+        pub block_propagation_messages: Counter,
+    }
+
+    impl SyncMetrics {
+        pub fn register(registry: &mut Registry) -> Arc<Self> {
+            let metrics = Arc::new(Self {
+                inbound_requests: Default::default(),
+                inbound_responses: Default::default(),
+                response_bytes: Family::new_with_constructor(|| {
+                    Histogram::new(exponential_buckets(256.0, 2.0, 16))
+                }),
+                handler_latency_seconds: Family::new_with_constructor(|| {
+                    Histogram::new(exponential_buckets(0.001, 2.0, 16))
+                }),
+                connected_peers: Default::default(),
+                banned_peers: Default::default(),
+                block_propagation_messages: Default::default(),
+            });
+
+            registry.register(
+                "sync_inbound_requests",
+                "Inbound sync requests received, by protocol",
+                metrics.inbound_requests.clone(),
+            );
+            registry.register(
+                "sync_inbound_responses",
+                "Inbound sync responses sent, by protocol",
+                metrics.inbound_responses.clone(),
+            );
+            registry.register(
+                "sync_response_bytes",
+                "Size in bytes of sync response payloads observed by the codec, by protocol",
+                metrics.response_bytes.clone(),
+            );
+            registry.register(
+                "sync_handler_latency_seconds",
+                "Time spent in handle_p2p_event per sync protocol",
+                metrics.handler_latency_seconds.clone(),
+            );
+            registry.register(
+                "sync_connected_peers",
+                "Number of currently connected sync peers",
+                metrics.connected_peers.clone(),
+            );
+            registry.register(
+                "sync_banned_peers",
+                "Number of currently banned sync peers",
+                metrics.banned_peers.clone(),
+            );
+            registry.register(
+                "sync_block_propagation_messages",
+                "Number of block propagation messages processed",
+                metrics.block_propagation_messages.clone(),
+            );
+
+            // Pre-populate every per-protocol series at zero, so a protocol
+            // that's never seen traffic still shows up instead of being
+            // absent from the exposition until its first event (standard
+            // Prometheus practice for Family metrics with a known label set).
+            for &protocol in super::protocol::PROTOCOLS {
+                let label = ProtocolLabel {
+                    protocol: protocol.to_owned(),
+                };
+                metrics.inbound_requests.get_or_create(&label);
+                metrics.inbound_responses.get_or_create(&label);
+                metrics.response_bytes.get_or_create(&label);
+                metrics.handler_latency_seconds.get_or_create(&label);
+            }
+
+            metrics
+        }
+    }
+
+    fn exponential_buckets(
+        start: f64,
+        factor: f64,
+        count: usize,
+    ) -> impl Iterator<Item = f64> + Clone {
+        std::iter::successors(Some(start), move |&x| Some(x * factor)).take(count)
+    }
+}
+
 pub(crate) mod codec {
     use std::marker::PhantomData;
+    use std::sync::Arc;
 
     use async_trait::async_trait;
     use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
@@ -53,17 +193,35 @@ pub(crate) mod codec {
     };
     use p2p_stream::Codec;
 
+    use super::metrics;
+    use super::metrics::SyncMetrics;
     use super::protocol;
 
     pub const ONE_MIB: usize = 1024 * 1024;
     pub const FOUR_MIB: usize = 4 * ONE_MIB;
 
+    /// Wire tag prefixed to every response frame, identifying how the
+    /// payload that follows the `unsigned_varint` length is encoded.
+    const CODEC_TAG_IDENTITY: u8 = 0;
+    const CODEC_TAG_ZSTD: u8 = 1;
+
+    /// Hard cap on the *compressed* frame size, checked before the frame is
+    /// read or decompressed. The decompressed size is bounded separately:
+    /// `read_payload` stops decoding once the output passes the protocol's
+    /// `RESPONSE_SIZE_LIMIT`, which is what stops a decompression bomb.
+    const MAX_COMPRESSED_RESPONSE_SIZE: usize = FOUR_MIB;
+
+    /// Requests are just range/hash-list queries, never bytecode or state
+    /// payloads, so a single limit comfortably covers every protocol.
+    pub const REQUEST_SIZE_LIMIT: usize = 128 * 1024;
+
     pub type Headers = SyncCodec<
         protocol::Headers,
         header::BlockHeadersRequest,
         header::BlockHeadersResponse,
         proto::header::BlockHeadersRequest,
         proto::header::BlockHeadersResponse,
+        REQUEST_SIZE_LIMIT,
         ONE_MIB,
     >;
 
@@ -73,6 +231,7 @@ pub(crate) mod codec {
         state::StateDiffsResponse,
         proto::state::StateDiffsRequest,
         proto::state::StateDiffsResponse,
+        REQUEST_SIZE_LIMIT,
         ONE_MIB,
     >;
 
@@ -82,50 +241,108 @@ pub(crate) mod codec {
         class::ClassesResponse,
         proto::class::ClassesRequest,
         proto::class::ClassesResponse,
+        REQUEST_SIZE_LIMIT,
         FOUR_MIB,
     >;
 
-    pub type Transactions = SyncCodec<
+    pub type Bodies = StreamingSyncCodec<
+        protocol::Bodies,
+        p2p_proto_v1::block::BlockBodiesRequest,
+        p2p_proto_v1::block::BlockBodiesResponse,
+        p2p_proto_v1::proto::block::BlockBodiesRequest,
+        p2p_proto_v1::proto::block::BlockBodiesResponse,
+        REQUEST_SIZE_LIMIT,
+        FOUR_MIB,
+    >;
+
+    pub type Transactions = StreamingSyncCodec<
         protocol::Transactions,
         transaction::TransactionsRequest,
         transaction::TransactionsResponse,
         proto::transaction::TransactionsRequest,
         proto::transaction::TransactionsResponse,
+        REQUEST_SIZE_LIMIT,
         ONE_MIB,
     >;
 
-    pub type Receipts = SyncCodec<
+    pub type Receipts = StreamingSyncCodec<
         protocol::Receipts,
         receipt::ReceiptsRequest,
         receipt::ReceiptsResponse,
         proto::receipt::ReceiptsRequest,
         proto::receipt::ReceiptsResponse,
+        REQUEST_SIZE_LIMIT,
         ONE_MIB,
     >;
 
-    pub type Events = SyncCodec<
+    pub type Events = StreamingSyncCodec<
         protocol::Events,
         event::EventsRequest,
         event::EventsResponse,
         proto::event::EventsRequest,
         proto::event::EventsResponse,
+        REQUEST_SIZE_LIMIT,
         ONE_MIB,
     >;
 
     #[derive(Clone, Debug)]
-    pub struct SyncCodec<Protocol, Req, Resp, ProstReq, ProstResp, const RESPONSE_SIZE_LIMIT: usize>(
-        PhantomData<(Protocol, Req, Resp, ProstReq, ProstResp)>,
-    );
+    pub struct SyncCodec<
+        Protocol,
+        Req,
+        Resp,
+        ProstReq,
+        ProstResp,
+        const REQUEST_SIZE_LIMIT: usize,
+        const RESPONSE_SIZE_LIMIT: usize,
+    > {
+        /// Opt-in zstd compression on the write side. Reading always honours
+        /// whatever codec tag the peer sent, so this only has to agree
+        /// locally with what we choose to send.
+        compress: bool,
+        /// Set through [`Self::with_metrics`] by `behaviour::build_swarm` when
+        /// metrics were configured; records the per-message response byte
+        /// histogram.
+        metrics: Option<Arc<SyncMetrics>>,
+        _marker: PhantomData<(Protocol, Req, Resp, ProstReq, ProstResp)>,
+    }
 
-    impl<A, B, C, D, E, const F: usize> Default for SyncCodec<A, B, C, D, E, F> {
+    impl<A, B, C, D, E, const F: usize, const G: usize> Default for SyncCodec<A, B, C, D, E, F, G> {
         fn default() -> Self {
-            Self(Default::default())
+            Self {
+                compress: false,
+                metrics: None,
+                _marker: Default::default(),
+            }
+        }
+    }
+
+    impl<A, B, C, D, E, const F: usize, const G: usize> SyncCodec<A, B, C, D, E, F, G> {
+        /// Enables zstd compression of outgoing response payloads. Incoming
+        /// responses are decompressed transparently regardless of this
+        /// setting, so peers can mix codec versions.
+        pub fn with_compression(mut self, compress: bool) -> Self {
+            self.compress = compress;
+            self
+        }
+
+        /// Attaches a metrics registry so the codec records the size of every
+        /// response payload it reads or writes.
+        pub fn with_metrics(mut self, metrics: Arc<SyncMetrics>) -> Self {
+            self.metrics = Some(metrics);
+            self
         }
     }
 
     #[async_trait]
-    impl<Protocol, Req, Resp, ProstReq, ProstResp, const RESPONSE_SIZE_LIMIT: usize> Codec
-        for SyncCodec<Protocol, Req, Resp, ProstReq, ProstResp, RESPONSE_SIZE_LIMIT>
+    impl<
+            Protocol,
+            Req,
+            Resp,
+            ProstReq,
+            ProstResp,
+            const REQUEST_SIZE_LIMIT: usize,
+            const RESPONSE_SIZE_LIMIT: usize,
+        > Codec for SyncCodec<Protocol, Req, Resp, ProstReq, ProstResp, REQUEST_SIZE_LIMIT, RESPONSE_SIZE_LIMIT>
     where
         Protocol: AsRef<str> + Send + Clone,
         Req: TryFromProtobuf<ProstReq> + ToProtobuf<ProstReq> + Send,
@@ -145,9 +362,7 @@ pub(crate) mod codec {
         where
             T: AsyncRead + Unpin + Send,
         {
-            let mut buf = Vec::new();
-
-            io.take(ONE_MIB as u64).read_to_end(&mut buf).await?;
+            let buf = read_request_bytes::<T, REQUEST_SIZE_LIMIT>(io).await?;
 
             let prost_dto = ProstReq::decode(buf.as_ref())?;
             let dto = Req::try_from_protobuf(prost_dto, std::any::type_name::<ProstReq>())?;
@@ -157,29 +372,18 @@ pub(crate) mod codec {
 
         async fn read_response<T>(
             &mut self,
-            _: &Self::Protocol,
-            mut io: &mut T,
+            protocol: &Self::Protocol,
+            io: &mut T,
         ) -> std::io::Result<Self::Response>
         where
             T: AsyncRead + Unpin + Send,
         {
-            let encoded_len: usize = unsigned_varint::aio::read_usize(&mut io)
-                .await
-                .map_err(Into::<std::io::Error>::into)?;
+            let buf = read_payload::<T, RESPONSE_SIZE_LIMIT>(io).await?;
 
-            if encoded_len > RESPONSE_SIZE_LIMIT {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!(
-                        "Encoded length {} exceeds the maximum buffer size {}",
-                        encoded_len, RESPONSE_SIZE_LIMIT
-                    ),
-                ));
+            if let Some(metrics) = &self.metrics {
+                record_response_bytes(metrics, protocol, buf.len());
             }
 
-            let mut buf = vec![0u8; encoded_len];
-            io.read_exact(&mut buf).await?;
-
             let prost_dto = ProstResp::decode(buf.as_ref())?;
             let dto = Resp::try_from_protobuf(prost_dto, std::any::type_name::<ProstResp>())?;
 
@@ -202,16 +406,532 @@ pub(crate) mod codec {
 
         async fn write_response<T>(
             &mut self,
-            _: &Self::Protocol,
+            protocol: &Self::Protocol,
             io: &mut T,
             response: Self::Response,
         ) -> std::io::Result<()>
         where
             T: AsyncWrite + Unpin + Send,
         {
-            let data = response.to_protobuf().encode_length_delimited_to_vec();
+            let data = response.to_protobuf().encode_to_vec();
+            let written = write_payload(io, self.compress, data).await?;
+
+            if let Some(metrics) = &self.metrics {
+                record_response_bytes(metrics, protocol, written);
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Reads a request body up to `REQUEST_SIZE_LIMIT`, shared by
+    /// [`SyncCodec::read_request`] and [`StreamingSyncCodec::read_request`]
+    /// (requests themselves are never chunked/streamed, only responses are).
+    async fn read_request_bytes<T, const REQUEST_SIZE_LIMIT: usize>(
+        io: &mut T,
+    ) -> std::io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut buf = Vec::new();
+
+        // Read one byte past the limit so a request of exactly
+        // `REQUEST_SIZE_LIMIT` bytes (legitimate) is distinguishable from one
+        // that's actually oversized: only the latter fills `buf` to
+        // `REQUEST_SIZE_LIMIT + 1`.
+        io.take(REQUEST_SIZE_LIMIT as u64 + 1)
+            .read_to_end(&mut buf)
+            .await?;
+
+        if buf.len() as u64 > REQUEST_SIZE_LIMIT as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Request exceeds the maximum size of {REQUEST_SIZE_LIMIT} bytes"),
+            ));
+        }
+
+        Ok(buf)
+    }
+
+    /// A single item of a streaming sync response:
+    /// [`Data`](ResponseFrame::Data) for every item the handler produces, and
+    /// a final [`Fin`](ResponseFrame::Fin) once the range is exhausted. This
+    /// is what lets the requester's read loop stop without waiting for the
+    /// substream to close.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum ResponseFrame<Resp> {
+        Data(Resp),
+        Fin,
+    }
+
+    impl<Resp> ResponseFrame<Resp> {
+        pub fn into_data(self) -> Option<Resp> {
+            match self {
+                ResponseFrame::Data(resp) => Some(resp),
+                ResponseFrame::Fin => None,
+            }
+        }
+
+        pub fn is_fin(&self) -> bool {
+            matches!(self, ResponseFrame::Fin)
+        }
+    }
+
+    const FRAME_TAG_DATA: u8 = 0;
+    const FRAME_TAG_FIN: u8 = 1;
+
+    /// [`Codec`] for the range-based sync protocols (transactions, receipts,
+    /// events, bodies): identical to [`SyncCodec`] except that [`Self::Response`] is
+    /// [`ResponseFrame`], so a range is sent as a sequence of `write_response`
+    /// calls terminated by one final `Fin` frame rather than a single
+    /// aggregated message.
+    #[derive(Clone, Debug)]
+    pub struct StreamingSyncCodec<
+        Protocol,
+        Req,
+        Resp,
+        ProstReq,
+        ProstResp,
+        const REQUEST_SIZE_LIMIT: usize,
+        const RESPONSE_SIZE_LIMIT: usize,
+    > {
+        compress: bool,
+        metrics: Option<Arc<SyncMetrics>>,
+        _marker: PhantomData<(Protocol, Req, Resp, ProstReq, ProstResp)>,
+    }
+
+    impl<A, B, C, D, E, const F: usize, const G: usize> Default
+        for StreamingSyncCodec<A, B, C, D, E, F, G>
+    {
+        fn default() -> Self {
+            Self {
+                compress: false,
+                metrics: None,
+                _marker: Default::default(),
+            }
+        }
+    }
+
+    impl<A, B, C, D, E, const F: usize, const G: usize> StreamingSyncCodec<A, B, C, D, E, F, G> {
+        pub fn with_compression(mut self, compress: bool) -> Self {
+            self.compress = compress;
+            self
+        }
+
+        pub fn with_metrics(mut self, metrics: Arc<SyncMetrics>) -> Self {
+            self.metrics = Some(metrics);
+            self
+        }
+    }
+
+    #[async_trait]
+    impl<
+            Protocol,
+            Req,
+            Resp,
+            ProstReq,
+            ProstResp,
+            const REQUEST_SIZE_LIMIT: usize,
+            const RESPONSE_SIZE_LIMIT: usize,
+        > Codec
+        for StreamingSyncCodec<
+            Protocol,
+            Req,
+            Resp,
+            ProstReq,
+            ProstResp,
+            REQUEST_SIZE_LIMIT,
+            RESPONSE_SIZE_LIMIT,
+        >
+    where
+        Protocol: AsRef<str> + Send + Clone,
+        Req: TryFromProtobuf<ProstReq> + ToProtobuf<ProstReq> + Send,
+        Resp: TryFromProtobuf<ProstResp> + ToProtobuf<ProstResp> + Send,
+        ProstReq: prost::Message + Default,
+        ProstResp: prost::Message + Default,
+    {
+        type Protocol = Protocol;
+        type Request = Req;
+        type Response = ResponseFrame<Resp>;
+
+        async fn read_request<T>(
+            &mut self,
+            _: &Self::Protocol,
+            io: &mut T,
+        ) -> std::io::Result<Self::Request>
+        where
+            T: AsyncRead + Unpin + Send,
+        {
+            let buf = read_request_bytes::<T, REQUEST_SIZE_LIMIT>(io).await?;
+
+            let prost_dto = ProstReq::decode(buf.as_ref())?;
+            let dto = Req::try_from_protobuf(prost_dto, std::any::type_name::<ProstReq>())?;
+
+            Ok(dto)
+        }
+
+        async fn read_response<T>(
+            &mut self,
+            protocol: &Self::Protocol,
+            io: &mut T,
+        ) -> std::io::Result<Self::Response>
+        where
+            T: AsyncRead + Unpin + Send,
+        {
+            let mut frame_tag = [0u8; 1];
+            io.read_exact(&mut frame_tag).await?;
+
+            match frame_tag[0] {
+                FRAME_TAG_FIN => Ok(ResponseFrame::Fin),
+                FRAME_TAG_DATA => {
+                    let buf = read_payload::<T, RESPONSE_SIZE_LIMIT>(io).await?;
+
+                    if let Some(metrics) = &self.metrics {
+                        record_response_bytes(metrics, protocol, buf.len());
+                    }
+
+                    let prost_dto = ProstResp::decode(buf.as_ref())?;
+                    let dto =
+                        Resp::try_from_protobuf(prost_dto, std::any::type_name::<ProstResp>())?;
+
+                    Ok(ResponseFrame::Data(dto))
+                }
+                tag => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Unknown frame tag {tag}"),
+                )),
+            }
+        }
+
+        async fn write_request<T>(
+            &mut self,
+            _: &Self::Protocol,
+            io: &mut T,
+            request: Self::Request,
+        ) -> std::io::Result<()>
+        where
+            T: AsyncWrite + Unpin + Send,
+        {
+            let data = request.to_protobuf().encode_to_vec();
             io.write_all(&data).await?;
             Ok(())
         }
+
+        async fn write_response<T>(
+            &mut self,
+            protocol: &Self::Protocol,
+            io: &mut T,
+            response: Self::Response,
+        ) -> std::io::Result<()>
+        where
+            T: AsyncWrite + Unpin + Send,
+        {
+            match response {
+                ResponseFrame::Fin => {
+                    io.write_all(&[FRAME_TAG_FIN]).await?;
+                    Ok(())
+                }
+                ResponseFrame::Data(response) => {
+                    io.write_all(&[FRAME_TAG_DATA]).await?;
+
+                    let data = response.to_protobuf().encode_to_vec();
+                    let written = write_payload(io, self.compress, data).await?;
+
+                    if let Some(metrics) = &self.metrics {
+                        record_response_bytes(metrics, protocol, written);
+                    }
+
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn record_response_bytes<Protocol: AsRef<str>>(
+        metrics: &SyncMetrics,
+        protocol: &Protocol,
+        len: usize,
+    ) {
+        metrics
+            .response_bytes
+            .get_or_create(&metrics::ProtocolLabel {
+                protocol: protocol.as_ref().to_owned(),
+            })
+            .observe(len as f64);
+    }
+
+    /// Reads one `[codec tag][unsigned_varint length][payload]` frame and
+    /// decompresses it if needed. An identity payload's length is checked
+    /// against `RESPONSE_SIZE_LIMIT`; a zstd payload's encoded length is
+    /// checked against [`MAX_COMPRESSED_RESPONSE_SIZE`] and its decompressed
+    /// length against `RESPONSE_SIZE_LIMIT`.
+    async fn read_payload<T, const RESPONSE_SIZE_LIMIT: usize>(
+        mut io: &mut T,
+    ) -> std::io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut codec_tag = [0u8; 1];
+        io.read_exact(&mut codec_tag).await?;
+
+        let encoded_len: usize = unsigned_varint::aio::read_usize(&mut io)
+            .await
+            .map_err(Into::<std::io::Error>::into)?;
+
+        let max_len = match codec_tag[0] {
+            CODEC_TAG_IDENTITY => RESPONSE_SIZE_LIMIT,
+            CODEC_TAG_ZSTD => MAX_COMPRESSED_RESPONSE_SIZE,
+            tag => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Unknown codec tag {tag}"),
+                ))
+            }
+        };
+
+        if encoded_len > max_len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Encoded length {} exceeds the maximum buffer size {}",
+                    encoded_len, max_len
+                ),
+            ));
+        }
+
+        let mut buf = vec![0u8; encoded_len];
+        io.read_exact(&mut buf).await?;
+
+        match codec_tag[0] {
+            CODEC_TAG_IDENTITY => Ok(buf),
+            CODEC_TAG_ZSTD => {
+                // `decode_all` would materialize however much the frame
+                // claims to expand to before we ever get to check its size —
+                // exactly the decompression bomb this limit exists to stop.
+                // Pull through the decoder instead and stop reading the
+                // instant we'd exceed the limit, so peak memory is bounded
+                // by `RESPONSE_SIZE_LIMIT` regardless of what's on the wire.
+                use std::io::Read;
+
+                let decoder = zstd::stream::read::Decoder::new(buf.as_slice())?;
+                let mut decoded = Vec::new();
+                decoder
+                    .take(RESPONSE_SIZE_LIMIT as u64 + 1)
+                    .read_to_end(&mut decoded)?;
+
+                if decoded.len() > RESPONSE_SIZE_LIMIT {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "Decompressed length exceeds the maximum buffer size {RESPONSE_SIZE_LIMIT}"
+                        ),
+                    ));
+                }
+                Ok(decoded)
+            }
+            _ => unreachable!("codec tag already validated above"),
+        }
+    }
+
+    /// Writes one `[codec tag][unsigned_varint length][payload]` frame,
+    /// compressing first if `compress` is set. Returns the length of the
+    /// (possibly compressed) payload actually written, for metrics.
+    async fn write_payload<T>(io: &mut T, compress: bool, data: Vec<u8>) -> std::io::Result<usize>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let (codec_tag, payload) = if compress {
+            (CODEC_TAG_ZSTD, zstd::stream::encode_all(data.as_slice(), 0)?)
+        } else {
+            (CODEC_TAG_IDENTITY, data)
+        };
+
+        let mut len_buf = unsigned_varint::encode::usize_buffer();
+        let len_buf = unsigned_varint::encode::usize(payload.len(), &mut len_buf);
+
+        io.write_all(&[codec_tag]).await?;
+        io.write_all(len_buf).await?;
+        io.write_all(&payload).await?;
+        Ok(payload.len())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use futures::io::Cursor;
+
+        use super::*;
+
+        #[derive(Clone, Debug, Default)]
+        struct FakeProtocol;
+
+        impl AsRef<str> for FakeProtocol {
+            fn as_ref(&self) -> &str {
+                "/test/1"
+            }
+        }
+
+        // A minimal Req/Resp/prost stand-in so the test doesn't need a real
+        // p2p_proto message: a single varint-free byte payload round-tripped
+        // verbatim through prost's `bytes` wire type via a one-field message.
+        #[derive(Clone, PartialEq, Eq, prost::Message)]
+        struct FakeProst {
+            #[prost(bytes = "vec", tag = "1")]
+            payload: Vec<u8>,
+        }
+
+        #[derive(Clone, Debug, PartialEq, Eq)]
+        struct FakeResp(Vec<u8>);
+
+        impl ToProtobuf<FakeProst> for FakeResp {
+            fn to_protobuf(self) -> FakeProst {
+                FakeProst { payload: self.0 }
+            }
+        }
+
+        impl TryFromProtobuf<FakeProst> for FakeResp {
+            fn try_from_protobuf(
+                input: FakeProst,
+                _: &'static str,
+            ) -> Result<Self, std::io::Error> {
+                Ok(FakeResp(input.payload))
+            }
+        }
+
+        type FakeStreamingCodec = StreamingSyncCodec<
+            FakeProtocol,
+            FakeProst,
+            FakeResp,
+            FakeProst,
+            FakeProst,
+            REQUEST_SIZE_LIMIT,
+            ONE_MIB,
+        >;
+
+        #[test]
+        fn streaming_response_round_trips_items_then_fin() {
+            futures::executor::block_on(async {
+                let protocol = FakeProtocol;
+                let mut codec = FakeStreamingCodec::default();
+                let mut buf = Cursor::new(Vec::new());
+
+                codec
+                    .write_response(
+                        &protocol,
+                        &mut buf,
+                        ResponseFrame::Data(FakeResp(b"one".to_vec())),
+                    )
+                    .await
+                    .unwrap();
+                codec
+                    .write_response(
+                        &protocol,
+                        &mut buf,
+                        ResponseFrame::Data(FakeResp(b"two".to_vec())),
+                    )
+                    .await
+                    .unwrap();
+                codec
+                    .write_response(&protocol, &mut buf, ResponseFrame::Fin)
+                    .await
+                    .unwrap();
+
+                buf.set_position(0);
+
+                let mut codec = FakeStreamingCodec::default();
+                let first = codec.read_response(&protocol, &mut buf).await.unwrap();
+                let second = codec.read_response(&protocol, &mut buf).await.unwrap();
+                let fin = codec.read_response(&protocol, &mut buf).await.unwrap();
+
+                assert_eq!(first, ResponseFrame::Data(FakeResp(b"one".to_vec())));
+                assert_eq!(second, ResponseFrame::Data(FakeResp(b"two".to_vec())));
+                assert!(fin.is_fin());
+            });
+        }
+
+        #[test]
+        fn read_request_accepts_exactly_the_size_limit_but_rejects_one_byte_more() {
+            // The bytes here aren't valid protobuf, so a request at exactly
+            // the limit still fails to decode -- what this pins down is that
+            // it fails for that reason and not the "exceeds the maximum"
+            // size-limit error, which must only fire one byte later.
+            futures::executor::block_on(async {
+                let protocol = FakeProtocol;
+                let mut codec = FakeStreamingCodec::default();
+
+                let mut at_limit = Cursor::new(vec![0u8; REQUEST_SIZE_LIMIT]);
+                let error = codec
+                    .read_request(&protocol, &mut at_limit)
+                    .await
+                    .unwrap_err();
+                assert!(!error.to_string().contains("exceeds the maximum"));
+
+                let mut over_limit = Cursor::new(vec![0u8; REQUEST_SIZE_LIMIT + 1]);
+                let error = codec
+                    .read_request(&protocol, &mut over_limit)
+                    .await
+                    .unwrap_err();
+                assert!(error.to_string().contains("exceeds the maximum"));
+            });
+        }
+
+        // `SyncCodec::read_request` (Headers/StateDiffs/Classes) delegates to
+        // the very same `read_request_bytes` exercised above -- both codecs'
+        // `read_request` boundary behaviour is pinned down by this one test
+        // on the shared helper, rather than two copies that could silently
+        // diverge.
+        #[test]
+        fn read_request_bytes_accepts_exactly_the_size_limit_but_rejects_one_byte_more() {
+            futures::executor::block_on(async {
+                let mut at_limit = Cursor::new(vec![0u8; REQUEST_SIZE_LIMIT]);
+                let result = read_request_bytes::<_, REQUEST_SIZE_LIMIT>(&mut at_limit).await;
+                assert_eq!(result.unwrap().len(), REQUEST_SIZE_LIMIT);
+
+                let mut over_limit = Cursor::new(vec![0u8; REQUEST_SIZE_LIMIT + 1]);
+                let error = read_request_bytes::<_, REQUEST_SIZE_LIMIT>(&mut over_limit)
+                    .await
+                    .unwrap_err();
+                assert!(error.to_string().contains("exceeds the maximum"));
+            });
+        }
+    }
+}
+
+/// Misbehavior scoring for sync peers, following the weighted-penalty
+/// approach used by substrate's networking: each kind of protocol violation
+/// carries its own weight, penalties accumulate against a peer in the shared
+/// `Peers` map, and crossing [`BAN_THRESHOLD`] gets the peer disconnected and
+/// temporarily excluded from redial and from being picked as a sync target.
+pub mod reputation {
+    /// A single protocol violation observed for a peer: classified by
+    /// [`crate::MainLoop`] from a sync codec error and handed to
+    /// `handle_p2p_event` in [`crate::Event::ProtocolViolation`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Misbehavior {
+        /// A request or response frame exceeded its protocol's size limit,
+        /// or carried an unknown codec or frame tag.
+        OversizedFrame,
+        /// A frame failed to decode as protobuf, or failed DTO conversion.
+        MalformedPayload,
     }
+
+    impl Misbehavior {
+        /// Penalty applied to a peer's reputation score for this violation.
+        /// Malformed payloads score worse than oversized frames: an oversized
+        /// frame can be an honest peer syncing a larger range than we expect,
+        /// while a malformed payload is never a legitimate protocol message.
+        pub const fn penalty(self) -> i32 {
+            match self {
+                Misbehavior::OversizedFrame => 10,
+                Misbehavior::MalformedPayload => 25,
+            }
+        }
+    }
+
+    /// Cumulative penalty at which a peer is disconnected and temporarily
+    /// banned from being re-dialed or used as a sync target.
+    pub const BAN_THRESHOLD: i32 = 100;
+
+    /// How long a peer that crossed [`BAN_THRESHOLD`] is excluded from redial
+    /// and from sync target selection.
+    pub const BAN_DURATION: std::time::Duration = std::time::Duration::from_secs(30 * 60);
 }
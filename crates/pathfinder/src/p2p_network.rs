@@ -1,16 +1,15 @@
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::Context;
 use p2p::client::{peer_agnostic, peer_aware};
 use p2p::libp2p::{identity::Keypair, multiaddr::Multiaddr, PeerId};
+use p2p::sync::metrics::{ProtocolLabel, SyncMetrics};
 use p2p::{HeadRx, HeadTx, Peers};
-use p2p_proto_v1::block::BlockBodiesResponseList;
-use p2p_proto_v1::event::EventsResponseList;
-use p2p_proto_v1::receipt::ReceiptsResponseList;
-use p2p_proto_v1::transaction::TransactionsResponseList;
 use pathfinder_common::{BlockHash, BlockNumber, ChainId};
 use pathfinder_storage::Storage;
-use tokio::sync::{mpsc, RwLock};
+use prometheus_client::registry::Registry;
+use tokio::sync::{mpsc, Mutex, RwLock};
 use tracing::Instrument;
 
 pub mod client;
@@ -31,6 +30,19 @@ pub struct P2PContext {
     pub keypair: Keypair,
     pub listen_on: Multiaddr,
     pub bootstrap_addresses: Vec<Multiaddr>,
+    /// When set, sync protocol metrics are registered into it under the
+    /// `sync_*` names documented in [`p2p::sync::metrics`]. Shared with the
+    /// caller so it can mount the registry on its own metrics HTTP endpoint.
+    pub metrics_registry: Option<Arc<Mutex<Registry>>>,
+    /// Enables mDNS so nodes on the same LAN auto-discover and dial each
+    /// other without an explicit bootstrap address. Handy for local
+    /// multi-node devnets and integration tests; should stay off for
+    /// public/production deployments.
+    pub enable_mdns: bool,
+    /// Enables zstd compression of outgoing sync responses. Meaningfully
+    /// cuts sync bandwidth at the cost of some CPU; off by default since not
+    /// every peer on the network is guaranteed to support it yet.
+    pub enable_compression: bool,
 }
 
 #[tracing::instrument(name = "p2p", skip_all)]
@@ -42,14 +54,33 @@ pub async fn start(context: P2PContext) -> anyhow::Result<P2PNetworkHandle> {
         keypair,
         listen_on,
         bootstrap_addresses,
+        metrics_registry,
+        enable_mdns,
+        enable_compression,
     } = context;
 
     let peer_id = keypair.public().to_peer_id();
     tracing::info!(%peer_id, "🖧 Starting P2P");
 
+    let metrics = match &metrics_registry {
+        Some(registry) => Some(SyncMetrics::register(&mut *registry.lock().await)),
+        None => None,
+    };
+
+    if enable_mdns {
+        tracing::info!("mDNS peer discovery enabled");
+    }
+
     let peers: Arc<RwLock<Peers>> = Arc::new(RwLock::new(Default::default()));
-    let (p2p_client, mut p2p_events, p2p_main_loop) =
-        p2p::new(keypair, peers.clone(), Default::default());
+    let (p2p_client, mut p2p_events, p2p_main_loop) = p2p::new(
+        keypair,
+        peers.clone(),
+        p2p::Config {
+            enable_mdns,
+            enable_compression,
+        },
+        metrics.clone(),
+    );
 
     let mut main_loop_handle = {
         let span = tracing::info_span!("behaviour");
@@ -96,6 +127,7 @@ pub async fn start(context: P2PContext) -> anyhow::Result<P2PNetworkHandle> {
 
     let join_handle = {
         let mut p2p_client = p2p_client.clone();
+        let peers = peers.clone();
         tokio::task::spawn(
             async move {
                 loop {
@@ -105,7 +137,7 @@ pub async fn start(context: P2PContext) -> anyhow::Result<P2PNetworkHandle> {
                             break;
                         }
                         Some(event) = p2p_events.recv() => {
-                            match handle_p2p_event(event, storage.clone(), &mut p2p_client, &mut tx).await {
+                            match handle_p2p_event(event, storage.clone(), &mut p2p_client, &peers, metrics.as_ref(), &mut tx).await {
                                 Ok(()) => {},
                                 Err(e) => { tracing::error!("Failed to handle P2P event: {}", e) },
                             }
@@ -129,17 +161,48 @@ async fn handle_p2p_event(
     event: p2p::Event,
     storage: Storage,
     p2p_client: &mut peer_aware::Client,
+    peers: &Arc<RwLock<Peers>>,
+    metrics: Option<&Arc<SyncMetrics>>,
     tx: &mut HeadTx,
 ) -> anyhow::Result<()> {
-    // FIXME
-    // This is because sync_handlers provide a channel while the p2p_client expects an entire collection
-    use futures::stream::StreamExt;
+    // sync_handlers feed items into an mpsc channel as they're produced; the
+    // streaming-response send methods below forward each item to the peer as
+    // soon as it arrives and close the substream with a `Fin` message once
+    // the channel is exhausted, instead of buffering the whole range here.
     use tokio_stream::wrappers::ReceiverStream;
 
+    // Records how long this event took to handle and, for inbound sync
+    // requests, that a response was sent, against the given protocol label.
+    let record = |protocol: &'static str, started: Instant| {
+        if let Some(metrics) = metrics {
+            let label = ProtocolLabel {
+                protocol: protocol.to_owned(),
+            };
+            metrics.inbound_responses.get_or_create(&label).inc();
+            metrics
+                .handler_latency_seconds
+                .get_or_create(&label)
+                .observe(started.elapsed().as_secs_f64());
+        }
+    };
+    let count_inbound_request = |protocol: &'static str| {
+        if let Some(metrics) = metrics {
+            metrics
+                .inbound_requests
+                .get_or_create(&ProtocolLabel {
+                    protocol: protocol.to_owned(),
+                })
+                .inc();
+        }
+    };
+
     match event {
         p2p::Event::InboundHeadersSyncRequest {
             request, channel, ..
         } => {
+            let started = Instant::now();
+            count_inbound_request(p2p::sync::protocol::Headers::NAME);
+
             let (rep_tx, mut rep_rx) = mpsc::channel(1);
             sync_handlers::v1::get_headers(storage, request, rep_tx).await?;
             p2p_client
@@ -148,15 +211,20 @@ async fn handle_p2p_event(
                     rep_rx.recv().await.expect("sender is not dropped"),
                 )
                 .await;
+
+            record(p2p::sync::protocol::Headers::NAME, started);
         }
         p2p::Event::InboundBodiesSyncRequest {
             request, channel, ..
         } => {
+            let started = Instant::now();
+            count_inbound_request(p2p::sync::protocol::Bodies::NAME);
             let (resp_tx, resp_rx) = mpsc::channel(1);
 
             let jh = tokio::spawn(sync_handlers::v1::get_bodies(storage, request, resp_tx));
-            let resp_stream = ReceiverStream::new(resp_rx);
-            let items: Vec<_> = resp_stream.collect().await;
+            p2p_client
+                .send_bodies_sync_response_stream(channel, ReceiverStream::new(resp_rx))
+                .await;
 
             match jh.await {
                 Ok(Err(error)) => tracing::error!("Sync handler failed: {error}"),
@@ -164,20 +232,21 @@ async fn handle_p2p_event(
                 _ => {}
             }
 
-            p2p_client
-                .send_bodies_sync_response(channel, BlockBodiesResponseList { items })
-                .await;
+            record(p2p::sync::protocol::Bodies::NAME, started);
         }
         p2p::Event::InboundTransactionsSyncRequest {
             request, channel, ..
         } => {
+            let started = Instant::now();
+            count_inbound_request(p2p::sync::protocol::Transactions::NAME);
             let (resp_tx, resp_rx) = mpsc::channel(1);
 
             let jh = tokio::spawn(sync_handlers::v1::get_transactions(
                 storage, request, resp_tx,
             ));
-            let resp_stream = ReceiverStream::new(resp_rx);
-            let items: Vec<_> = resp_stream.collect().await;
+            p2p_client
+                .send_transactions_sync_response_stream(channel, ReceiverStream::new(resp_rx))
+                .await;
 
             match jh.await {
                 Ok(Err(error)) => tracing::error!("Sync handler failed: {error}"),
@@ -185,18 +254,19 @@ async fn handle_p2p_event(
                 _ => {}
             }
 
-            p2p_client
-                .send_transactions_sync_response(channel, TransactionsResponseList { items })
-                .await;
+            record(p2p::sync::protocol::Transactions::NAME, started);
         }
         p2p::Event::InboundReceiptsSyncRequest {
             request, channel, ..
         } => {
+            let started = Instant::now();
+            count_inbound_request(p2p::sync::protocol::Receipts::NAME);
             let (resp_tx, resp_rx) = mpsc::channel(1);
 
             let jh = tokio::spawn(sync_handlers::v1::get_receipts(storage, request, resp_tx));
-            let resp_stream = ReceiverStream::new(resp_rx);
-            let items: Vec<_> = resp_stream.collect().await;
+            p2p_client
+                .send_receipts_sync_response_stream(channel, ReceiverStream::new(resp_rx))
+                .await;
 
             match jh.await {
                 Ok(Err(error)) => tracing::error!("Sync handler failed: {error}"),
@@ -204,18 +274,19 @@ async fn handle_p2p_event(
                 _ => {}
             }
 
-            p2p_client
-                .send_receipts_sync_response(channel, ReceiptsResponseList { items })
-                .await;
+            record(p2p::sync::protocol::Receipts::NAME, started);
         }
         p2p::Event::InboundEventsSyncRequest {
             request, channel, ..
         } => {
+            let started = Instant::now();
+            count_inbound_request(p2p::sync::protocol::Events::NAME);
             let (resp_tx, resp_rx) = mpsc::channel(1);
 
             let jh = tokio::spawn(sync_handlers::v1::get_events(storage, request, resp_tx));
-            let resp_stream = ReceiverStream::new(resp_rx);
-            let items: Vec<_> = resp_stream.collect().await;
+            p2p_client
+                .send_events_sync_response_stream(channel, ReceiverStream::new(resp_rx))
+                .await;
 
             match jh.await {
                 Ok(Err(error)) => tracing::error!("Sync handler failed: {error}"),
@@ -223,11 +294,12 @@ async fn handle_p2p_event(
                 _ => {}
             }
 
-            p2p_client
-                .send_events_sync_response(channel, EventsResponseList { items })
-                .await;
+            record(p2p::sync::protocol::Events::NAME, started);
         }
         p2p::Event::BlockPropagation { from, new_block } => {
+            if let Some(metrics) = metrics {
+                metrics.block_propagation_messages.inc();
+            }
             tracing::info!(%from, ?new_block, "Block Propagation");
             use p2p_proto_v1::block::{BlockHeadersResponse, BlockHeadersResponsePart, NewBlock};
 
@@ -249,7 +321,37 @@ async fn handle_p2p_event(
                 }
             }
         }
-        p2p::Event::SyncPeerConnected { .. } | p2p::Event::Test(_) => { /* Ignore me */ }
+        p2p::Event::ProtocolViolation { from, violation } => {
+            let penalty = violation.penalty();
+            let banned = peers.write().await.record_misbehavior(from, penalty);
+            tracing::warn!(peer=%from, ?violation, penalty, "Peer misbehaved on a sync protocol");
+
+            if banned {
+                tracing::warn!(peer=%from, "Peer crossed the misbehavior threshold, disconnecting and banning");
+                p2p_client.disconnect(from).await;
+                p2p_client.ban_peer(from, p2p::sync::reputation::BAN_DURATION).await;
+
+                if let Some(metrics) = metrics {
+                    metrics.banned_peers.set(peers.read().await.banned_count() as i64);
+                }
+            }
+        }
+        p2p::Event::SyncPeerConnected { .. } | p2p::Event::SyncPeerDisconnected { .. } => {
+            // Re-reads both gauges off `peers` rather than incrementing or
+            // decrementing them in place. `connected_count` needs this to
+            // avoid monotonically increasing; `banned_count` needs it
+            // because bans expire after BAN_DURATION with nothing else to
+            // re-derive the gauge, so it would otherwise stay stale and
+            // over-report indefinitely once violations stop occurring. This
+            // tick (peer churn) is a reasonably frequent, already-available
+            // hook to recompute it on, short of a dedicated timer.
+            if let Some(metrics) = metrics {
+                let peers = peers.read().await;
+                metrics.connected_peers.set(peers.connected_count() as i64);
+                metrics.banned_peers.set(peers.banned_count() as i64);
+            }
+        }
+        p2p::Event::Test(_) => { /* Ignore me */ }
     }
 
     Ok(())